@@ -0,0 +1,57 @@
+use std::{
+    env, fs,
+    path::PathBuf,
+};
+
+use color_eyre::{eyre::eyre, Result};
+use serde::{Deserialize, Serialize};
+
+/// A player as remembered between runs, enough to answer offline queries.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct CachedPlayer {
+    pub(crate) pid: i64,
+    pub(crate) name: String,
+    pub(crate) model: String,
+}
+
+/// Persisted settings and cache stored in the platform config directory.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(crate) struct Config {
+    /// Last receiver actually used, reused when `--host` is omitted.
+    pub(crate) host: Option<String>,
+    /// Preferred player id, served without a live `get_players` round-trip.
+    pub(crate) default_pid: Option<i64>,
+    /// Players discovered on the most recent successful query.
+    #[serde(default)]
+    pub(crate) players: Vec<CachedPlayer>,
+}
+
+impl Config {
+    /// Load the config, returning the default when none exists yet.
+    pub(crate) fn load() -> Result<Self> {
+        match fs::read_to_string(Self::path()?) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Persist the config, creating the config directory as needed.
+    pub(crate) fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Location of the config file, honouring `XDG_CONFIG_HOME`.
+    fn path() -> Result<PathBuf> {
+        let base = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .ok_or_else(|| eyre!("could not determine config directory"))?;
+        Ok(base.join("denounce").join("config.json"))
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use color_eyre::{eyre::eyre, Result};
+
+/// SSDP multicast endpoint used by UPnP and HEOS devices.
+const SSDP_MULTICAST: &str = "239.255.255.250:1900";
+
+/// Search target advertised by Denon/HEOS receivers.
+const DENON_ST: &str = "urn:schemas-denon-com:device:ACT-Denon:1";
+
+/// A receiver located on the local network through SSDP.
+#[derive(Debug, Clone)]
+pub(crate) struct Discovered {
+    /// Host or IP address the receiver can be reached on.
+    pub(crate) host: String,
+    /// Human readable name taken from the device description, if available.
+    pub(crate) name: Option<String>,
+}
+
+/// Perform an SSDP `M-SEARCH` and return every Denon receiver that answers.
+///
+/// The socket listens for unicast replies for a few seconds; each reply's
+/// `LOCATION` header points at a description XML whose host becomes the
+/// connection target, and whose `<friendlyName>` supplies a label.
+pub(crate) fn discover(timeout: Duration) -> Result<Vec<Discovered>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(Duration::from_millis(500)))?;
+
+    let search = format!(
+        "M-SEARCH * HTTP/1.1\r\n\
+         HOST: {SSDP_MULTICAST}\r\n\
+         MAN: \"ssdp:discover\"\r\n\
+         MX: 3\r\n\
+         ST: {DENON_ST}\r\n\
+         \r\n"
+    );
+    socket.send_to(search.as_bytes(), SSDP_MULTICAST)?;
+
+    let mut found: Vec<Discovered> = Vec::new();
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 2048];
+    while Instant::now() < deadline {
+        let len = match socket.recv_from(&mut buf) {
+            Ok((len, _)) => len,
+            Err(ref e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let reply = String::from_utf8_lossy(&buf[..len]);
+        let Some(location) = header_value(&reply, "LOCATION") else {
+            continue;
+        };
+        let Some(host) = host_from_url(location) else {
+            continue;
+        };
+        if found.iter().any(|d| d.host == host) {
+            continue;
+        }
+        let name = friendly_name(location);
+        found.push(Discovered { host, name });
+    }
+    Ok(found)
+}
+
+/// Extract the value of an (case-insensitive) HTTP-style header line.
+fn header_value<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+    response.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// Pull the host component out of an `http://host:port/...` URL.
+fn host_from_url(url: &str) -> Option<String> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.split_once(':').map_or(authority, |(h, _)| h);
+    (!host.is_empty()).then(|| host.to_owned())
+}
+
+/// Best-effort fetch of the `<friendlyName>` from a device description URL.
+fn friendly_name(location: &str) -> Option<String> {
+    let body = http_get(location).ok()?;
+    let start = body.find("<friendlyName>")? + "<friendlyName>".len();
+    let end = body[start..].find("</friendlyName>")? + start;
+    Some(body[start..end].trim().to_owned())
+}
+
+/// Minimal blocking HTTP GET used only to read device description XML.
+fn http_get(url: &str) -> Result<String> {
+    let rest = url
+        .split_once("://")
+        .map(|(_, rest)| rest)
+        .ok_or_else(|| eyre!("unsupported url: {url}"))?;
+    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let addr = authority
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| eyre!("could not resolve {authority}"))?;
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    write!(
+        stream,
+        "GET /{path} HTTP/1.0\r\nHost: {authority}\r\nConnection: close\r\n\r\n"
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response
+        .split_once("\r\n\r\n")
+        .map_or(response.as_str(), |(_, body)| body)
+        .to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_value_is_case_insensitive_and_trimmed() {
+        let response = "HTTP/1.1 200 OK\r\nLOCATION: http://10.0.0.5:60006/desc.xml\r\nST: x\r\n";
+        assert_eq!(
+            header_value(response, "location"),
+            Some("http://10.0.0.5:60006/desc.xml"),
+        );
+        assert_eq!(header_value(response, "missing"), None);
+    }
+
+    #[test]
+    fn host_from_url_extracts_host() {
+        assert_eq!(
+            host_from_url("http://10.0.0.5:60006/desc.xml"),
+            Some("10.0.0.5".into()),
+        );
+        assert_eq!(host_from_url("http://receiver.local/"), Some("receiver.local".into()));
+        assert_eq!(host_from_url("10.0.0.5:80/x"), Some("10.0.0.5".into()));
+        assert_eq!(host_from_url("http://"), None);
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    path::{Path, PathBuf},
+    thread::spawn,
+};
+
+use color_eyre::{eyre::eyre, Result};
+
+/// A tiny HTTP server that hands a single local file to the receiver.
+///
+/// The listener is bound to the machine's LAN-facing address so the receiver
+/// can reach it, serves the file with the right `Content-Type` and honours
+/// `Range` requests. It answers every incoming connection on a background
+/// thread for the rest of the process; dropping the `FileServer` does not
+/// stop it.
+pub(crate) struct FileServer {
+    addr: SocketAddr,
+    name: String,
+}
+
+impl FileServer {
+    /// Start serving `path`, reachable from `receiver_host`.
+    pub(crate) fn start(path: &Path, receiver_host: &str) -> Result<Self> {
+        let path = path.canonicalize()?;
+        if !path.is_file() {
+            return Err(eyre!("{} is not a file", path.display()));
+        }
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| eyre!("file has no usable name"))?
+            .to_owned();
+
+        let ip = lan_address(receiver_host)?;
+        let listener = TcpListener::bind((ip, 0))?;
+        let addr = listener.local_addr()?;
+
+        let served = path.clone();
+        spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let served = served.clone();
+                spawn(move || {
+                    let _ = handle(stream, &served);
+                });
+            }
+        });
+
+        Ok(Self { addr, name })
+    }
+
+    /// The URL the receiver should stream from.
+    pub(crate) fn url(&self) -> String {
+        format!("http://{}/{}", self.addr, encode_path(&self.name))
+    }
+}
+
+/// Determine which local address the receiver can reach us on.
+fn lan_address(receiver_host: &str) -> Result<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect((receiver_host, 1255))?;
+    Ok(socket.local_addr()?.ip())
+}
+
+/// Serve one HTTP request for `path`, supporting a single byte range.
+fn handle(mut stream: TcpStream, path: &PathBuf) -> Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    loop {
+        let mut line = String::new();
+        if std::io::BufRead::read_line(&mut reader, &mut line)? == 0 {
+            return Ok(());
+        }
+        if line == "\r\n" || line == "\n" {
+            break;
+        }
+        request.push_str(&line);
+    }
+
+    let mut file = File::open(path)?;
+    let total = file.metadata()?.len();
+    let content_type = mime_for(path);
+    let range = request
+        .lines()
+        .find_map(|l| l.strip_prefix("Range:").or_else(|| l.strip_prefix("range:")))
+        .and_then(|v| parse_range(v.trim(), total));
+
+    match range {
+        Some((start, end)) => {
+            let len = end - start + 1;
+            file.seek(SeekFrom::Start(start))?;
+            write!(
+                stream,
+                "HTTP/1.1 206 Partial Content\r\n\
+                 Content-Type: {content_type}\r\n\
+                 Accept-Ranges: bytes\r\n\
+                 Content-Range: bytes {start}-{end}/{total}\r\n\
+                 Content-Length: {len}\r\n\r\n"
+            )?;
+            copy_n(&mut file, &mut stream, len)?;
+        }
+        None => {
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\n\
+                 Content-Type: {content_type}\r\n\
+                 Accept-Ranges: bytes\r\n\
+                 Content-Length: {total}\r\n\r\n"
+            )?;
+            std::io::copy(&mut file, &mut stream)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy exactly `len` bytes from `src` to `dst`.
+fn copy_n(src: &mut impl Read, dst: &mut impl Write, len: u64) -> Result<()> {
+    let mut remaining = len;
+    let mut buf = [0u8; 64 * 1024];
+    while remaining > 0 {
+        let want = remaining.min(buf.len() as u64) as usize;
+        let read = src.read(&mut buf[..want])?;
+        if read == 0 {
+            break;
+        }
+        dst.write_all(&buf[..read])?;
+        remaining -= read as u64;
+    }
+    Ok(())
+}
+
+/// Parse a single `bytes=start-end` range against a known total length.
+///
+/// Handles open-ended ranges (`bytes=500-`) and suffix ranges
+/// (`bytes=-500`, meaning the last 500 bytes).
+fn parse_range(value: &str, total: u64) -> Option<(u64, u64)> {
+    let spec = value.strip_prefix("bytes=")?;
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = if start.is_empty() {
+        // Suffix range: the final `end` bytes of the file.
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 {
+            return None;
+        }
+        (total.saturating_sub(suffix), total.saturating_sub(1))
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end: u64 = if end.is_empty() {
+            total.saturating_sub(1)
+        } else {
+            end.parse().ok()?
+        };
+        (start, end)
+    };
+    (start <= end && end < total).then_some((start, end))
+}
+
+/// Percent-encode the characters in a path segment that HTTP clients choke on.
+fn encode_path(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for b in name.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+/// Guess a `Content-Type` from the file extension.
+fn mime_for(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+    {
+        Some("mp3") => "audio/mpeg",
+        Some("flac") => "audio/flac",
+        Some("wav") => "audio/wav",
+        Some("ogg" | "oga") => "audio/ogg",
+        Some("m4a" | "aac") => "audio/aac",
+        Some("opus") => "audio/opus",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_range_handles_bounded_open_and_suffix() {
+        assert_eq!(parse_range("bytes=0-499", 1000), Some((0, 499)));
+        assert_eq!(parse_range("bytes=500-", 1000), Some((500, 999)));
+        assert_eq!(parse_range("bytes=-500", 1000), Some((500, 999)));
+        assert_eq!(parse_range("bytes=-1500", 1000), Some((0, 999)));
+    }
+
+    #[test]
+    fn parse_range_rejects_invalid() {
+        assert_eq!(parse_range("items=0-1", 1000), None);
+        assert_eq!(parse_range("bytes=900-800", 1000), None);
+        assert_eq!(parse_range("bytes=1000-1200", 1000), None);
+        assert_eq!(parse_range("bytes=-0", 1000), None);
+    }
+
+    #[test]
+    fn encode_path_escapes_reserved_characters() {
+        assert_eq!(encode_path("song.mp3"), "song.mp3");
+        assert_eq!(encode_path("a b.mp3"), "a%20b.mp3");
+        assert_eq!(encode_path("na%me?.flac"), "na%25me%3F.flac");
+    }
+
+    #[test]
+    fn mime_for_maps_known_extensions() {
+        assert_eq!(mime_for(Path::new("x.mp3")), "audio/mpeg");
+        assert_eq!(mime_for(Path::new("x.FLAC")), "audio/flac");
+        assert_eq!(mime_for(Path::new("x.bin")), "application/octet-stream");
+        assert_eq!(mime_for(Path::new("noext")), "application/octet-stream");
+    }
+}
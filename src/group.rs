@@ -0,0 +1,187 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use color_eyre::Result;
+
+use crate::{heos, Denon};
+
+/// Maximum tolerated drift between a member and the leader before realigning.
+const DRIFT_THRESHOLD_MS: i64 = 750;
+
+/// How long to let the group settle after a realign before checking drift
+/// again, so a freshly restarted group is not immediately flagged as drifted.
+const REALIGN_COOLDOWN: Duration = Duration::from_secs(5);
+
+/// A coordinated intent fanned out across every room in the group.
+///
+/// Modelling the shared state as tagged operations lets one command drive all
+/// members at once rather than poking each device individually.
+#[derive(Debug, Clone, Copy)]
+enum SyncOp {
+    /// Propagate a play/pause/stop transition to the whole group.
+    SetPlaying { state: PlayState },
+    /// Restart the whole group from the top so the rooms resume in lock-step.
+    ///
+    /// HEOS exposes no absolute seek over the CLI, so this is a *restart*, not
+    /// a seek to `leader_pos` — members cannot be nudged to an arbitrary
+    /// position. `leader_pos` is carried only to decide whether a restart is
+    /// worthwhile (a group already near the start is left alone).
+    RestartGroup { leader_pos: i64 },
+}
+
+/// The play states the group toggle fans out.
+#[derive(Debug, Clone, Copy)]
+enum PlayState {
+    Play,
+    Pause,
+    Stop,
+}
+
+impl PlayState {
+    fn from_heos(state: &str) -> Option<Self> {
+        match state {
+            "play" => Some(PlayState::Play),
+            "pause" => Some(PlayState::Pause),
+            "stop" => Some(PlayState::Stop),
+            _ => None,
+        }
+    }
+    fn as_heos(self) -> &'static str {
+        match self {
+            PlayState::Play => "play",
+            PlayState::Pause => "pause",
+            PlayState::Stop => "stop",
+        }
+    }
+}
+
+/// Create the group and keep its members coordinated with the leader.
+///
+/// Blocks on the change-event stream, restarting the whole group in lock-step
+/// whenever a member drifts past [`DRIFT_THRESHOLD_MS`] and mirroring the
+/// leader's play state to every room. Note this is a restart, not a seek:
+/// HEOS has no CLI seek primitive (see [`SyncOp::RestartGroup`]).
+pub(crate) fn run(denon: &mut Denon, leader: i64, members: Vec<i64>) -> Result<()> {
+    let mut pids = vec![leader];
+    pids.extend(members.iter().copied());
+    let list = pids
+        .iter()
+        .map(i64::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    denon.send_command(&format!("heos://group/set_group?pid={list}"))?;
+
+    let mut events = denon.subscribe_events()?;
+    let mut positions: HashMap<i64, i64> = HashMap::new();
+    let mut last_realign: Option<Instant> = None;
+    while let Some(event) = events.next_event()? {
+        let op = match event {
+            heos::HeosEvent::PlayerNowPlayingProgress { pid, cur_pos, .. } => {
+                positions.insert(pid, cur_pos);
+                // Suppress drift checks while the group is still settling after
+                // a realign; otherwise the leader advancing would re-trigger a
+                // restart on every progress event and the rooms would thrash.
+                let cooling = last_realign.is_some_and(|t| t.elapsed() < REALIGN_COOLDOWN);
+                if cooling {
+                    None
+                } else {
+                    drifted_member(&positions, leader, &members)
+                        .map(|leader_pos| SyncOp::RestartGroup { leader_pos })
+                }
+            }
+            heos::HeosEvent::PlayerStateChanged { pid, ref state } if pid == leader => {
+                PlayState::from_heos(state).map(|state| SyncOp::SetPlaying { state })
+            }
+            _ => None,
+        };
+        if let Some(op) = op {
+            if matches!(op, SyncOp::RestartGroup { .. }) {
+                last_realign = Some(Instant::now());
+                positions.clear();
+            }
+            apply(denon, &pids, op)?;
+        }
+    }
+    Ok(())
+}
+
+/// Return the leader's current position when any member has drifted.
+fn drifted_member(positions: &HashMap<i64, i64>, leader: i64, members: &[i64]) -> Option<i64> {
+    let leader_pos = *positions.get(&leader)?;
+    members.iter().copied().find_map(|pid| {
+        let pos = *positions.get(&pid)?;
+        ((pos - leader_pos).abs() > DRIFT_THRESHOLD_MS).then_some(leader_pos)
+    })
+}
+
+/// Execute a [`SyncOp`] against the relevant players.
+fn apply(denon: &mut Denon, pids: &[i64], op: SyncOp) -> Result<()> {
+    match op {
+        SyncOp::SetPlaying { state } => {
+            for pid in pids {
+                denon.send_command(&format!(
+                    "heos://player/set_play_state?pid={pid}&state={}",
+                    state.as_heos()
+                ))?;
+            }
+            Ok(())
+        }
+        // HEOS exposes no absolute seek over the CLI, so the only primitive that
+        // re-aligns rooms is restarting them together: stop every member, then
+        // start every member, so they resume the current queue in lock-step.
+        // A group already near the start of a track is left alone.
+        SyncOp::RestartGroup { leader_pos } => {
+            if leader_pos <= DRIFT_THRESHOLD_MS {
+                return Ok(());
+            }
+            for pid in pids {
+                denon.send_command(&format!(
+                    "heos://player/set_play_state?pid={pid}&state=stop"
+                ))?;
+            }
+            for pid in pids {
+                denon.send_command(&format!(
+                    "heos://player/set_play_state?pid={pid}&state=play"
+                ))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drifted_member_flags_only_beyond_threshold() {
+        let mut positions = HashMap::new();
+        positions.insert(1, 10_000); // leader
+        positions.insert(2, 10_500); // within threshold
+        assert_eq!(drifted_member(&positions, 1, &[2]), None);
+
+        positions.insert(3, 8_000); // drifted behind
+        assert_eq!(drifted_member(&positions, 1, &[2, 3]), Some(10_000));
+    }
+
+    #[test]
+    fn drifted_member_waits_for_leader_and_member_positions() {
+        let mut positions = HashMap::new();
+        // No leader position yet.
+        positions.insert(2, 5_000);
+        assert_eq!(drifted_member(&positions, 1, &[2]), None);
+        // Leader known but member not yet reported.
+        positions.insert(1, 5_000);
+        assert_eq!(drifted_member(&positions, 1, &[9]), None);
+    }
+
+    #[test]
+    fn play_state_round_trips() {
+        assert_eq!(PlayState::from_heos("play").map(PlayState::as_heos), Some("play"));
+        assert_eq!(PlayState::from_heos("pause").map(PlayState::as_heos), Some("pause"));
+        assert_eq!(PlayState::from_heos("stop").map(PlayState::as_heos), Some("stop"));
+        assert!(PlayState::from_heos("weird").is_none());
+    }
+}
@@ -1,15 +1,46 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{sleep, spawn},
+    time::Duration,
+};
+
+use color_eyre::{eyre::eyre, Result};
 use serde::Deserialize;
 
+/// TCP port the HEOS CLI protocol listens on.
+const HEOS_PORT: u16 = 1255;
+
+/// Command that (re)subscribes to change events after a reconnect.
+const SUBSCRIBE: &str = "heos://system/register_for_change_events?enable=on";
+
 #[derive(Deserialize, Debug)]
 pub(crate) struct Response<Payload> {
     pub(crate) heos: Header,
     pub(crate) payload: Payload,
 }
 
+/// A bare HEOS frame, used when only the header matters (e.g. events).
+#[derive(Deserialize, Debug)]
+pub(crate) struct Frame {
+    pub(crate) heos: Header,
+}
+
 #[derive(Deserialize, Debug)]
 pub(crate) struct Header {
     pub(crate) command: String,
-    pub(crate) result: HeosResult,
+    /// Command replies carry a result; `event/...` frames do not.
+    #[serde(default)]
+    pub(crate) result: Option<HeosResult>,
+    /// Present on replies and most events, but absent on e.g.
+    /// `event/groups_changed`.
+    #[serde(default)]
     pub(crate) message: String,
 }
 
@@ -20,6 +51,289 @@ pub(crate) enum HeosResult {
     Fail,
 }
 
+/// The `get_now_playing_media` payload for the current track.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct NowPlaying {
+    #[serde(default)]
+    pub(crate) song: String,
+    #[serde(default)]
+    pub(crate) album: String,
+    #[serde(default)]
+    pub(crate) artist: String,
+    #[serde(default)]
+    pub(crate) image_url: String,
+    #[serde(default)]
+    pub(crate) mid: String,
+}
+
+/// A change event pushed by the receiver after `register_for_change_events`.
+///
+/// Events arrive as `Frame` headers whose `command` is e.g.
+/// `event/player_state_changed` and whose `message` carries the fields as a
+/// query string (`pid=1&state=play`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum HeosEvent {
+    PlayerStateChanged { pid: i64, state: String },
+    PlayerVolumeChanged { pid: i64, level: i64, mute: String },
+    PlayerNowPlayingChanged { pid: i64 },
+    PlayerNowPlayingProgress { pid: i64, cur_pos: i64, duration: i64 },
+    GroupsChanged,
+    SourcesChanged,
+    /// Any event not modelled above, kept as the raw command suffix and fields.
+    Other { command: String, fields: Vec<(String, String)> },
+}
+
+impl HeosEvent {
+    /// Parse an `event/...` command together with its query-string message.
+    ///
+    /// Returns `None` when `command` is not an event.
+    pub(crate) fn parse(command: &str, message: &str) -> Option<Self> {
+        let suffix = command.strip_prefix("event/")?;
+        let fields = parse_message(message);
+        let get = |key: &str| {
+            fields
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+        };
+        let num = |key: &str| get(key).and_then(|v| v.parse::<i64>().ok());
+        Some(match suffix {
+            "player_state_changed" => HeosEvent::PlayerStateChanged {
+                pid: num("pid")?,
+                state: get("state")?,
+            },
+            "player_volume_changed" => HeosEvent::PlayerVolumeChanged {
+                pid: num("pid")?,
+                level: num("level")?,
+                mute: get("mute").unwrap_or_default(),
+            },
+            "player_now_playing_changed" => {
+                HeosEvent::PlayerNowPlayingChanged { pid: num("pid")? }
+            }
+            "player_now_playing_progress" => HeosEvent::PlayerNowPlayingProgress {
+                pid: num("pid")?,
+                cur_pos: num("cur_pos")?,
+                duration: num("duration")?,
+            },
+            "groups_changed" => HeosEvent::GroupsChanged,
+            "sources_changed" => HeosEvent::SourcesChanged,
+            _ => HeosEvent::Other {
+                command: suffix.to_owned(),
+                fields,
+            },
+        })
+    }
+}
+
+/// Look up a single field by key in a query-string-style `message`.
+pub(crate) fn message_field(message: &str, key: &str) -> Option<String> {
+    parse_message(message)
+        .into_iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v)
+}
+
+/// Split a `key=value&key=value` message into URL-decoded pairs.
+fn parse_message(message: &str) -> Vec<(String, String)> {
+    message
+        .split('&')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (key, value) = part.split_once('=').unwrap_or((part, ""));
+            (url_decode(key), url_decode(value))
+        })
+        .collect()
+}
+
+/// Decode the subset of percent-encoding HEOS uses in event messages.
+///
+/// Decoded octets are collected into a byte buffer and interpreted as UTF-8,
+/// so multi-byte sequences (e.g. `%C3%A9` for `é`) round-trip correctly.
+fn url_decode(input: &str) -> String {
+    let mut out: Vec<u8> = Vec::with_capacity(input.len());
+    let bytes = input.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hi = bytes.get(i + 1).copied().and_then(hex_digit);
+                let lo = bytes.get(i + 2).copied().and_then(hex_digit);
+                if let (Some(hi), Some(lo)) = (hi, lo) {
+                    out.push(hi * 16 + lo);
+                    i += 2;
+                } else {
+                    // Not a valid escape: keep the `%` and the bytes after it.
+                    out.push(b'%');
+                }
+            }
+            b'+' => out.push(b' '),
+            other => out.push(other),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Parse a single ASCII hex digit into its numeric value.
+fn hex_digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+/// Map of outstanding requests keyed by the command they expect a reply for.
+type Pending = Arc<Mutex<HashMap<String, VecDeque<SyncSender<String>>>>>;
+
+/// An async HEOS connection with request/response correlation.
+///
+/// A background reader thread parses newline-delimited JSON frames, routes
+/// `event/...` frames to the event stream and matches every other frame to
+/// the oldest pending request for that command. Callers therefore enqueue a
+/// command and await its correlated reply, which is safe to do while a change
+/// subscription is active. A dropped socket is transparently re-established
+/// and, if previously subscribed, re-registered for events.
+pub(crate) struct Connection {
+    writer: Arc<Mutex<TcpStream>>,
+    pending: Pending,
+    subscribed: Arc<AtomicBool>,
+    events: Option<Receiver<HeosEvent>>,
+}
+
+impl Connection {
+    /// Open a connection and spawn its reader thread.
+    pub(crate) fn connect(host: &str) -> Result<Self> {
+        let stream = TcpStream::connect((host, HEOS_PORT))?;
+        let writer = Arc::new(Mutex::new(stream.try_clone()?));
+        let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+        let subscribed = Arc::new(AtomicBool::new(false));
+        let (events_tx, events_rx) = mpsc::channel();
+
+        spawn({
+            let host = host.to_owned();
+            let writer = writer.clone();
+            let pending = pending.clone();
+            let subscribed = subscribed.clone();
+            move || reader_loop(host, stream, writer, pending, subscribed, events_tx)
+        });
+
+        Ok(Self {
+            writer,
+            pending,
+            subscribed,
+            events: Some(events_rx),
+        })
+    }
+
+    /// Enqueue `url` and block until its correlated reply line arrives.
+    pub(crate) fn request(&self, url: &str) -> Result<String> {
+        let key = command_key(url);
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .push_back(tx);
+        writeln!(self.writer.lock().unwrap(), "{url}")?;
+        rx.recv()
+            .map_err(|_| eyre!("connection closed before reply to {url}"))
+    }
+
+    /// Register for change events and take ownership of the event stream.
+    ///
+    /// Can only be called once; subsequent calls error.
+    pub(crate) fn subscribe(&mut self) -> Result<EventStream> {
+        self.subscribed.store(true, Ordering::SeqCst);
+        writeln!(self.writer.lock().unwrap(), "{SUBSCRIBE}")?;
+        let rx = self
+            .events
+            .take()
+            .ok_or_else(|| eyre!("event stream already taken"))?;
+        Ok(EventStream { rx })
+    }
+}
+
+/// Reader thread: parse frames, dispatch them, and reconnect on drop.
+fn reader_loop(
+    host: String,
+    mut stream: TcpStream,
+    writer: Arc<Mutex<TcpStream>>,
+    pending: Pending,
+    subscribed: Arc<AtomicBool>,
+    events_tx: Sender<HeosEvent>,
+) {
+    loop {
+        let mut lines = BufReader::new(stream).lines();
+        for line in lines.by_ref() {
+            match line {
+                Ok(line) => dispatch(line, &pending, &events_tx),
+                Err(_) => break,
+            }
+        }
+
+        // The socket dropped: wake every waiter with an error, then reconnect.
+        pending.lock().unwrap().clear();
+        stream = loop {
+            match TcpStream::connect((host.as_str(), HEOS_PORT)) {
+                Ok(fresh) => {
+                    if let Ok(write_half) = fresh.try_clone() {
+                        *writer.lock().unwrap() = write_half;
+                    }
+                    if subscribed.load(Ordering::SeqCst) {
+                        let _ = writeln!(writer.lock().unwrap(), "{SUBSCRIBE}");
+                    }
+                    break fresh;
+                }
+                Err(_) => sleep(Duration::from_secs(1)),
+            }
+        };
+    }
+}
+
+/// Route a single frame to an event listener or a pending request.
+fn dispatch(line: String, pending: &Pending, events_tx: &Sender<HeosEvent>) {
+    let Ok(frame) = serde_json::from_str::<Frame>(&line) else {
+        return;
+    };
+    if let Some(event) = HeosEvent::parse(&frame.heos.command, &frame.heos.message) {
+        let _ = events_tx.send(event);
+        return;
+    }
+    if let Some(queue) = pending.lock().unwrap().get_mut(&frame.heos.command) {
+        if let Some(tx) = queue.pop_front() {
+            let _ = tx.send(line);
+        }
+    }
+}
+
+/// Derive the reply command a `heos://a/b?query` URL will be answered with.
+fn command_key(url: &str) -> String {
+    url.strip_prefix("heos://")
+        .unwrap_or(url)
+        .split(['?', '\r', '\n'])
+        .next()
+        .unwrap_or("")
+        .to_owned()
+}
+
+/// Blocking stream of change events delivered by the reader thread.
+pub(crate) struct EventStream {
+    rx: Receiver<HeosEvent>,
+}
+
+impl EventStream {
+    /// Block until the next event arrives; `Ok(None)` once the stream ends.
+    pub(crate) fn next_event(&mut self) -> Result<Option<HeosEvent>> {
+        Ok(self.rx.recv().ok())
+    }
+}
+
+impl Iterator for EventStream {
+    type Item = HeosEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rx.recv().ok()
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub(crate) struct Player {
     pub(crate) name: String,
@@ -30,3 +344,155 @@ pub(crate) struct Player {
     pub(crate) lineout: u8,
     pub(crate) serial: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_decode_handles_plus_percent_and_utf8() {
+        let cases = [
+            ("", ""),
+            ("plain", "plain"),
+            ("a+b", "a b"),
+            ("%20", " "),
+            ("caf%C3%A9", "café"),
+            ("%zz", "%zz"),
+            ("trailing%", "trailing%"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(url_decode(input), expected, "decoding {input:?}");
+        }
+    }
+
+    #[test]
+    fn parse_message_splits_and_decodes_pairs() {
+        assert_eq!(
+            parse_message("pid=1&state=play"),
+            vec![("pid".into(), "1".into()), ("state".into(), "play".into())],
+        );
+        assert_eq!(parse_message(""), Vec::<(String, String)>::new());
+        assert_eq!(
+            parse_message("name=Living+Room&empty="),
+            vec![
+                ("name".into(), "Living Room".into()),
+                ("empty".into(), "".into()),
+            ],
+        );
+    }
+
+    #[test]
+    fn heos_event_parses_known_commands() {
+        assert_eq!(
+            HeosEvent::parse("event/player_state_changed", "pid=1&state=play"),
+            Some(HeosEvent::PlayerStateChanged {
+                pid: 1,
+                state: "play".into(),
+            }),
+        );
+        assert_eq!(
+            HeosEvent::parse("event/player_now_playing_progress", "pid=2&cur_pos=1500&duration=3000"),
+            Some(HeosEvent::PlayerNowPlayingProgress {
+                pid: 2,
+                cur_pos: 1500,
+                duration: 3000,
+            }),
+        );
+        assert_eq!(HeosEvent::parse("event/groups_changed", ""), Some(HeosEvent::GroupsChanged));
+    }
+
+    #[test]
+    fn heos_event_rejects_non_events_and_keeps_unknown() {
+        assert_eq!(HeosEvent::parse("player/get_players", ""), None);
+        assert!(matches!(
+            HeosEvent::parse("event/something_new", "foo=bar"),
+            Some(HeosEvent::Other { .. }),
+        ));
+    }
+
+    #[test]
+    fn command_key_strips_scheme_and_query() {
+        assert_eq!(command_key("heos://player/get_players"), "player/get_players");
+        assert_eq!(
+            command_key("heos://player/set_play_state?pid=1&state=play"),
+            "player/set_play_state",
+        );
+        assert_eq!(command_key("bare"), "bare");
+    }
+
+    #[test]
+    fn dispatch_routes_event_frame_to_event_stream() {
+        // A real event frame: no `result`, query-string `message`.
+        let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+        let (tx, rx) = mpsc::channel();
+        dispatch(
+            r#"{"heos":{"command":"event/player_state_changed","message":"pid=1&state=play"}}"#
+                .to_owned(),
+            &pending,
+            &tx,
+        );
+        assert_eq!(
+            rx.try_recv().ok(),
+            Some(HeosEvent::PlayerStateChanged {
+                pid: 1,
+                state: "play".into(),
+            }),
+        );
+    }
+
+    #[test]
+    fn dispatch_routes_message_less_event() {
+        // `event/groups_changed` carries neither `result` nor `message`.
+        let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+        let (tx, rx) = mpsc::channel();
+        dispatch(
+            r#"{"heos":{"command":"event/groups_changed"}}"#.to_owned(),
+            &pending,
+            &tx,
+        );
+        assert_eq!(rx.try_recv().ok(), Some(HeosEvent::GroupsChanged));
+    }
+
+    #[test]
+    fn dispatch_routes_reply_to_pending_request() {
+        let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+        let (events_tx, _events_rx) = mpsc::channel();
+        let (reply_tx, reply_rx) = mpsc::sync_channel(1);
+        pending
+            .lock()
+            .unwrap()
+            .entry("player/get_players".to_owned())
+            .or_default()
+            .push_back(reply_tx);
+        let line =
+            r#"{"heos":{"command":"player/get_players","result":"success","message":""},"payload":[]}"#
+                .to_owned();
+        dispatch(line.clone(), &pending, &events_tx);
+        assert_eq!(reply_rx.try_recv().ok(), Some(line));
+    }
+
+    #[test]
+    fn event_stream_yields_dispatched_events_end_to_end() {
+        // Exercise the full JSON -> dispatch -> EventStream path, not just the
+        // leaf parser, so a Header/Frame mismatch would be caught here.
+        let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+        let (tx, rx) = mpsc::channel();
+        let mut stream = EventStream { rx };
+        dispatch(
+            r#"{"heos":{"command":"event/player_volume_changed","message":"pid=3&level=20&mute=off"}}"#
+                .to_owned(),
+            &pending,
+            &tx,
+        );
+        drop(tx); // closing the sender lets the stream terminate after draining
+        assert_eq!(
+            stream.next_event().unwrap(),
+            Some(HeosEvent::PlayerVolumeChanged {
+                pid: 3,
+                level: 20,
+                mute: "off".into(),
+            }),
+        );
+        assert_eq!(stream.next_event().unwrap(), None);
+    }
+}
@@ -7,13 +7,18 @@ use std::{
 use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use color_eyre::{eyre::eyre, Result};
 use rustyline::{error::ReadlineError, DefaultEditor, ExternalPrinter};
-use serde::Deserialize;
 
 #[derive(Parser)]
 #[command(version, about)]
 struct Cli {
-    #[arg(long, value_name = "IP", default_value = "192.168.0.209")]
-    host: String,
+    /// Address of the receiver. When omitted, the last-used or a discovered
+    /// receiver is used.
+    #[arg(long, value_name = "IP")]
+    host: Option<String>,
+
+    /// Answer player queries from the local cache without touching the network.
+    #[arg(long)]
+    offline: bool,
 
     #[command(subcommand)]
     command: Command,
@@ -22,6 +27,8 @@ struct Cli {
 #[derive(Subcommand)]
 enum Command {
     GenerateCompletions,
+    /// Locate HEOS/Denon receivers on the local network over SSDP.
+    Discover,
     #[command(alias("si"))]
     SelectInput {
         input: Input,
@@ -37,12 +44,38 @@ enum Command {
         pid: Option<i64>,
         url: String,
     },
+    /// Stream a local audio file to the receiver over a temporary HTTP server.
+    #[command(alias("file"))]
+    PlayFile {
+        #[arg(long)]
+        pid: Option<i64>,
+        path: std::path::PathBuf,
+    },
     /// Send an arbitrary text command.
     ///
     /// If no command is provided, interactive shell will be opened.
     Text {
         command: Option<String>,
     },
+    /// Group players for synchronized whole-home playback.
+    ///
+    /// The first id (or `--leader`) leads; remaining ids join as members and
+    /// are kept aligned with the leader until interrupted.
+    #[command(alias("sync"))]
+    Group {
+        #[arg(long)]
+        leader: Option<i64>,
+        members: Vec<i64>,
+    },
+    /// Expose the receiver on the session bus as an MPRIS2 media player.
+    ///
+    /// Runs until interrupted, bridging media keys and desktop controls to
+    /// the selected HEOS player.
+    #[command(alias("daemon"))]
+    Mpris {
+        #[arg(long)]
+        pid: Option<i64>,
+    },
     /// Send an arbitrary HEOS command.
     ///
     /// If no command is provided, interactive shell will be opened.
@@ -100,14 +133,18 @@ impl Input {
 struct Denon {
     host: String,
     text_session: Option<TcpStream>,
-    heos_session: Option<TcpStream>,
+    heos_session: Option<heos::Connection>,
+    config: config::Config,
+    offline: bool,
 }
 impl Denon {
-    fn with_host(host: String) -> Self {
+    fn with_host(host: String, config: config::Config, offline: bool) -> Self {
         Self {
             host,
             text_session: None,
             heos_session: None,
+            config,
+            offline,
         }
     }
     fn connect_text(&mut self) -> Result<&mut TcpStream> {
@@ -116,9 +153,9 @@ impl Denon {
         }
         Ok(self.text_session.as_mut().unwrap())
     }
-    fn connect_heos(&mut self) -> Result<&mut TcpStream> {
+    fn connect_heos(&mut self) -> Result<&mut heos::Connection> {
         if self.heos_session.is_none() {
-            self.heos_session = Some(TcpStream::connect((self.host.clone(), 1255))?);
+            self.heos_session = Some(heos::Connection::connect(&self.host)?);
         }
         Ok(self.heos_session.as_mut().unwrap())
     }
@@ -146,15 +183,54 @@ impl Denon {
     }
     fn heos_command(&mut self, url: Option<String>, subscribe: bool) -> Result<()> {
         if let Some(url) = url {
-            Ok(writeln!(self.connect_heos()?, "{url}")?)
+            println!("{}", self.connect_heos()?.request(&url)?);
+            Ok(())
+        } else if subscribe {
+            self.subscribe_shell()
         } else {
-            let mut stream = self.connect_heos()?.try_clone()?;
-            if subscribe {
-                writeln!(stream, "heos://system/register_for_change_events?enable=on")?;
+            self.heos_shell()
+        }
+    }
+    /// Register for change events and open a shell whose output is the typed
+    /// [`heos::HeosEvent`] stream rather than raw bytes.
+    fn subscribe_shell(&mut self) -> Result<()> {
+        let mut events = self.subscribe_events()?;
+        let mut rl = rustyline::Editor::<(), _>::with_history(
+            rustyline::Config::default(),
+            rustyline::history::MemHistory::new(),
+        )?;
+        let mut printer = rl.create_external_printer()?;
+        let _rxer: JoinHandle<Result<()>> = spawn(move || {
+            while let Some(event) = events.next_event()? {
+                printer.print(format!("{event:?}\n"))?;
             }
-            self.shell_helper(stream, b'\n')
+            Ok(())
+        });
+        self.heos_repl(&mut rl)
+    }
+    /// Interactive HEOS shell: each entered line is sent over the HEOS
+    /// connection and its correlated reply is printed.
+    fn heos_shell(&mut self) -> Result<()> {
+        let mut rl = rustyline::Editor::<(), _>::with_history(
+            rustyline::Config::default(),
+            rustyline::history::MemHistory::new(),
+        )?;
+        self.heos_repl(&mut rl)
+    }
+    /// Read commands from `rl`, dispatch them to HEOS, and print the replies.
+    fn heos_repl(&mut self, rl: &mut rustyline::Editor<(), rustyline::history::MemHistory>) -> Result<()> {
+        loop {
+            let command = match rl.readline(">>> ") {
+                Err(ReadlineError::Eof) => return Ok(()),
+                c => c?,
+            };
+            println!("{}", self.connect_heos()?.request(&command)?);
         }
     }
+    /// Enable change events and return the typed event stream.
+    fn subscribe_events(&mut self) -> Result<heos::EventStream> {
+        self.connect_heos()?.subscribe()
+    }
     fn shell_helper(&mut self, stream: TcpStream, split: u8) -> Result<()> {
         let mut rl = rustyline::Editor::<(), _>::with_history(
             rustyline::Config::default(),
@@ -179,17 +255,111 @@ impl Denon {
             writeln!(stream, "{command}")?;
         }
     }
+    /// Send a HEOS command and await its correlated success/failure reply.
+    fn send_command(&mut self, url: &str) -> Result<()> {
+        let line = self.connect_heos()?.request(url)?;
+        let frame: heos::Frame = serde_json::from_str(&line)?;
+        if matches!(frame.heos.result, Some(heos::HeosResult::Fail)) {
+            return Err(eyre!("command failed: {url} ({})", frame.heos.message));
+        }
+        Ok(())
+    }
+    /// Serve a local file over HTTP and stream it to the receiver, blocking
+    /// until playback stops or the user interrupts.
+    fn play_file(&mut self, pid: Option<i64>, path: &std::path::Path) -> Result<()> {
+        let pid = if let Some(pid) = pid {
+            pid
+        } else {
+            self.get_first_player_id()?
+        };
+        let server = fileserve::FileServer::start(path, &self.host)?;
+        let url = server.url();
+        let mut events = self.subscribe_events()?;
+        self.send_command(&format!("heos://browse/play_stream?pid={pid}&url={url}"))?;
+        // Keep the server alive until the receiver stops playing our stream.
+        while let Some(event) = events.next_event()? {
+            if let heos::HeosEvent::PlayerStateChanged { pid: p, state } = event {
+                if p == pid && state == "stop" {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+    fn get_now_playing(&mut self, pid: i64) -> Result<heos::NowPlaying> {
+        let line = self
+            .connect_heos()?
+            .request(&format!("heos://player/get_now_playing_media?pid={pid}"))?;
+        let response: heos::Response<heos::NowPlaying> = serde_json::from_str(&line)?;
+        if matches!(response.heos.result, Some(heos::HeosResult::Fail)) {
+            return Err(eyre!("failed to get now playing: {:?}", response));
+        }
+        Ok(response.payload)
+    }
+    /// Query the current play state (`play`/`pause`/`stop`) for a player.
+    fn get_play_state(&mut self, pid: i64) -> Result<String> {
+        let line = self
+            .connect_heos()?
+            .request(&format!("heos://player/get_play_state?pid={pid}"))?;
+        let frame: heos::Frame = serde_json::from_str(&line)?;
+        if matches!(frame.heos.result, Some(heos::HeosResult::Fail)) {
+            return Err(eyre!("failed to get play state: {:?}", frame.heos));
+        }
+        heos::message_field(&frame.heos.message, "state")
+            .ok_or_else(|| eyre!("no state in reply: {}", frame.heos.message))
+    }
+    /// Query the current volume (0-100) for a player.
+    fn get_volume(&mut self, pid: i64) -> Result<i64> {
+        let line = self
+            .connect_heos()?
+            .request(&format!("heos://player/get_volume?pid={pid}"))?;
+        let frame: heos::Frame = serde_json::from_str(&line)?;
+        if matches!(frame.heos.result, Some(heos::HeosResult::Fail)) {
+            return Err(eyre!("failed to get volume: {:?}", frame.heos));
+        }
+        heos::message_field(&frame.heos.message, "level")
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| eyre!("no level in reply: {}", frame.heos.message))
+    }
     fn get_players(&mut self) -> Result<Vec<heos::Player>> {
-        let mut session = self.connect_heos()?;
-        writeln!(&mut session, "heos://player/get_players")?;
-        let mut de = serde_json::Deserializer::from_reader(session);
-        let response = heos::Response::<Vec<heos::Player>>::deserialize(&mut de)?;
-        if matches!(response.heos.result, heos::HeosResult::Fail) {
+        let line = self.connect_heos()?.request("heos://player/get_players")?;
+        let response: heos::Response<Vec<heos::Player>> = serde_json::from_str(&line)?;
+        if matches!(response.heos.result, Some(heos::HeosResult::Fail)) {
             return Err(eyre!("failed to get players: {:?}", response));
         }
+        self.cache_players(&response.payload);
         Ok(response.payload)
     }
+    /// Refresh the persisted cache opportunistically after a live query.
+    fn cache_players(&mut self, players: &[heos::Player]) {
+        self.config.host = Some(self.host.clone());
+        self.config.players = players
+            .iter()
+            .map(|p| config::CachedPlayer {
+                pid: p.pid,
+                name: p.name.clone(),
+                model: p.model.clone(),
+            })
+            .collect();
+        if self.config.default_pid.is_none() {
+            self.config.default_pid = players.first().map(|p| p.pid);
+        }
+        let _ = self.config.save();
+    }
     fn get_first_player_id(&mut self) -> Result<i64> {
+        // Offline: serve the preferred pid, else the first cached player.
+        if self.offline {
+            if let Some(pid) = self.config.default_pid {
+                return Ok(pid);
+            }
+            return self
+                .config
+                .players
+                .first()
+                .map(|p| p.pid)
+                .ok_or_else(|| eyre!("offline and player cache is empty"));
+        }
+        // Online: always reflect the live first player and refresh the cache.
         Ok(self
             .get_players()?
             .first()
@@ -202,26 +372,51 @@ impl Denon {
         } else {
             self.get_first_player_id()?
         };
-        let mut session = self.connect_heos()?;
-        writeln!(
-            &mut session,
-            "heos://browse/play_stream?pid={pid}&url={url}"
-        )?;
-        let mut de = serde_json::Deserializer::from_reader(session);
-        let response = heos::Response::<()>::deserialize(&mut de)?;
-        if matches!(response.heos.result, heos::HeosResult::Fail) {
-            return Err(eyre!("failed to get players: {:?}", response));
-        }
-        Ok(response.payload)
+        self.send_command(&format!("heos://browse/play_stream?pid={pid}&url={url}"))
     }
 }
 
+mod config;
+mod discover;
+mod fileserve;
+mod group;
 mod heos;
+mod mpris;
+
+/// Seconds to wait for SSDP replies before giving up.
+const DISCOVERY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(4);
+
+/// Resolve a receiver host: the explicit flag, the last-used cached host, then
+/// SSDP discovery. In offline mode discovery is skipped.
+fn resolve_host(host: Option<String>, config: &config::Config, offline: bool) -> Result<String> {
+    if let Some(host) = host {
+        return Ok(host);
+    }
+    if let Some(host) = config.host.clone() {
+        return Ok(host);
+    }
+    if offline {
+        return Err(eyre!("offline and no cached host; pass --host"));
+    }
+    let mut found = discover::discover(DISCOVERY_TIMEOUT)?;
+    match found.len() {
+        0 => Err(eyre!("no receivers discovered; pass --host")),
+        1 => Ok(found.remove(0).host),
+        _ => {
+            eprintln!("multiple receivers found, pass one with --host:");
+            for d in &found {
+                match &d.name {
+                    Some(name) => eprintln!("  {} ({name})", d.host),
+                    None => eprintln!("  {}", d.host),
+                }
+            }
+            Err(eyre!("ambiguous receiver, pass --host"))
+        }
+    }
+}
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    let host = cli.host;
-    let mut denon = Denon::with_host(host);
     match cli.command {
         Command::GenerateCompletions => {
             clap_complete::generate(
@@ -230,7 +425,28 @@ fn main() -> Result<()> {
                 "denounce",
                 &mut std::io::stdout(),
             );
+            return Ok(());
         }
+        Command::Discover => {
+            let found = discover::discover(DISCOVERY_TIMEOUT)?;
+            if found.is_empty() {
+                return Err(eyre!("no receivers discovered"));
+            }
+            for d in &found {
+                match &d.name {
+                    Some(name) => println!("{}\t{name}", d.host),
+                    None => println!("{}", d.host),
+                }
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+    let cfg = config::Config::load()?;
+    let host = resolve_host(cli.host, &cfg, cli.offline)?;
+    let mut denon = Denon::with_host(host, cfg, cli.offline);
+    match cli.command {
+        Command::GenerateCompletions | Command::Discover => unreachable!(),
         Command::SelectInput { input } => {
             denon.select_input(input)?;
         }
@@ -243,9 +459,30 @@ fn main() -> Result<()> {
         Command::PlayUrl { pid, url } => {
             denon.play_url(pid, url)?;
         }
+        Command::PlayFile { pid, path } => {
+            denon.play_file(pid, &path)?;
+        }
         Command::Text { command } => {
             denon.text_command(command)?;
         }
+        Command::Group { leader, mut members } => {
+            let leader = if let Some(leader) = leader {
+                leader
+            } else if !members.is_empty() {
+                members.remove(0)
+            } else {
+                denon.get_first_player_id()?
+            };
+            group::run(&mut denon, leader, members)?;
+        }
+        Command::Mpris { pid } => {
+            let pid = if let Some(pid) = pid {
+                pid
+            } else {
+                denon.get_first_player_id()?
+            };
+            mpris::run(denon, pid)?;
+        }
         Command::Heos { url, subscribe } => {
             denon.heos_command(url, subscribe)?;
         }
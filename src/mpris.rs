@@ -0,0 +1,303 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    thread::spawn,
+};
+
+use color_eyre::Result;
+use zbus::{
+    blocking::{connection, object_server::InterfaceRef},
+    interface,
+    zvariant::{ObjectPath, Value},
+};
+
+use crate::{heos, Denon};
+
+/// Bus name the player registers under on the session bus.
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.denounce";
+
+/// D-Bus object path every MPRIS player is expected to expose.
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+
+/// State reflected over D-Bus, kept in sync by the change-event loop.
+#[derive(Default)]
+struct State {
+    /// `play` / `pause` / `stop` as reported by HEOS.
+    play_state: String,
+    now_playing: heos::NowPlaying,
+    /// Current track position in microseconds (MPRIS `Position` unit).
+    position_us: i64,
+    /// Track length in microseconds, or zero when unknown.
+    length_us: i64,
+    /// Volume as a 0.0-1.0 fraction.
+    volume: f64,
+}
+
+/// Shared bridge handle used by both MPRIS interfaces and the event loop.
+#[derive(Clone)]
+struct Bridge {
+    denon: Arc<Mutex<Denon>>,
+    state: Arc<Mutex<State>>,
+    pid: i64,
+}
+
+impl Bridge {
+    fn send(&self, url: &str) -> Result<(), zbus::fdo::Error> {
+        self.denon
+            .lock()
+            .unwrap()
+            .send_command(url)
+            .map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+    }
+}
+
+/// The root `org.mpris.MediaPlayer2` interface.
+struct Root;
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+    #[zbus(property)]
+    fn identity(&self) -> &str {
+        "denounce"
+    }
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        vec!["http".into(), "https".into()]
+    }
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+/// The `org.mpris.MediaPlayer2.Player` interface backed by HEOS.
+struct Player {
+    bridge: Bridge,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn play(&self) -> Result<(), zbus::fdo::Error> {
+        self.bridge.send(&self.set_play_state_url("play"))
+    }
+    fn pause(&self) -> Result<(), zbus::fdo::Error> {
+        self.bridge.send(&self.set_play_state_url("pause"))
+    }
+    fn stop(&self) -> Result<(), zbus::fdo::Error> {
+        self.bridge.send(&self.set_play_state_url("stop"))
+    }
+    fn play_pause(&self) -> Result<(), zbus::fdo::Error> {
+        let playing = self.bridge.state.lock().unwrap().play_state == "play";
+        self.bridge
+            .send(&self.set_play_state_url(if playing { "pause" } else { "play" }))
+    }
+    fn next(&self) -> Result<(), zbus::fdo::Error> {
+        self.bridge
+            .send(&format!("heos://player/play_next?pid={}", self.bridge.pid))
+    }
+    fn previous(&self) -> Result<(), zbus::fdo::Error> {
+        self.bridge.send(&format!(
+            "heos://player/play_previous?pid={}",
+            self.bridge.pid
+        ))
+    }
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        match self.bridge.state.lock().unwrap().play_state.as_str() {
+            "play" => "Playing",
+            "pause" => "Paused",
+            _ => "Stopped",
+        }
+        .to_owned()
+    }
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, Value<'static>> {
+        let state = self.bridge.state.lock().unwrap();
+        let np = &state.now_playing;
+        let mut map = HashMap::new();
+        map.insert("mpris:trackid".into(), Value::from(track_id(&np.mid)));
+        if state.length_us > 0 {
+            map.insert("mpris:length".into(), Value::from(state.length_us));
+        }
+        map.insert("xesam:title".into(), Value::from(np.song.clone()));
+        map.insert("xesam:album".into(), Value::from(np.album.clone()));
+        map.insert("xesam:artist".into(), Value::from(vec![np.artist.clone()]));
+        if !np.image_url.is_empty() {
+            map.insert("mpris:artUrl".into(), Value::from(np.image_url.clone()));
+        }
+        map
+    }
+    #[zbus(property)]
+    fn position(&self) -> i64 {
+        self.bridge.state.lock().unwrap().position_us
+    }
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        self.bridge.state.lock().unwrap().volume
+    }
+    #[zbus(property)]
+    fn set_volume(&self, volume: f64) -> Result<(), zbus::fdo::Error> {
+        let level = (volume.clamp(0.0, 1.0) * 100.0).round() as i64;
+        self.bridge.send(&format!(
+            "heos://player/set_volume?pid={}&level={level}",
+            self.bridge.pid
+        ))
+    }
+    #[zbus(property)]
+    fn can_control(&self) -> bool {
+        true
+    }
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        false
+    }
+}
+
+impl Player {
+    fn set_play_state_url(&self, state: &str) -> String {
+        format!(
+            "heos://player/set_play_state?pid={}&state={state}",
+            self.bridge.pid
+        )
+    }
+}
+
+/// Build a valid MPRIS track id path from a HEOS media id.
+fn track_id(mid: &str) -> ObjectPath<'static> {
+    let sanitized: String = mid
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    ObjectPath::try_from(format!("/org/mpris/denounce/track/{sanitized}"))
+        .unwrap_or_else(|_| ObjectPath::from_static_str_unchecked("/org/mpris/denounce/track/0"))
+}
+
+/// Register the MPRIS player on the session bus and bridge it to HEOS.
+///
+/// Blocks forever, keeping the local state in sync from the change-event
+/// stream and emitting `PropertiesChanged` so desktops reflect live state.
+pub(crate) fn run(mut denon: Denon, pid: i64) -> Result<()> {
+    // Seed the cache with real queries so clients see state immediately.
+    let now_playing = denon.get_now_playing(pid).unwrap_or_default();
+    let play_state = denon.get_play_state(pid).unwrap_or_default();
+    let volume = denon.get_volume(pid).unwrap_or(100) as f64 / 100.0;
+    let events = denon.subscribe_events()?;
+
+    let bridge = Bridge {
+        denon: Arc::new(Mutex::new(denon)),
+        state: Arc::new(Mutex::new(State {
+            play_state,
+            now_playing,
+            position_us: 0,
+            length_us: 0,
+            volume,
+        })),
+        pid,
+    };
+
+    let connection = connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, Root)?
+        .serve_at(
+            OBJECT_PATH,
+            Player {
+                bridge: bridge.clone(),
+            },
+        )?
+        .build()?;
+
+    let player_ref = connection
+        .object_server()
+        .interface::<_, Player>(OBJECT_PATH)?;
+
+    spawn(move || event_loop(bridge, events, player_ref));
+
+    // The connection serves requests on its own task; park this thread.
+    loop {
+        std::thread::park();
+    }
+}
+
+/// Fold change events into the shared state, re-querying when tracks change
+/// and emitting `PropertiesChanged` for state, volume and track changes.
+/// Progress events update position and track length without a signal
+/// (MPRIS clients poll position), so they do not spam the bus.
+fn event_loop(
+    bridge: Bridge,
+    mut events: heos::EventStream,
+    player: InterfaceRef<Player>,
+) -> Result<()> {
+    while let Some(event) = events.next_event()? {
+        match event {
+            heos::HeosEvent::PlayerStateChanged { pid, state } if pid == bridge.pid => {
+                bridge.state.lock().unwrap().play_state = state;
+                notify(&player);
+            }
+            heos::HeosEvent::PlayerVolumeChanged { pid, level, .. } if pid == bridge.pid => {
+                bridge.state.lock().unwrap().volume = level as f64 / 100.0;
+                notify(&player);
+            }
+            heos::HeosEvent::PlayerNowPlayingProgress {
+                pid,
+                cur_pos,
+                duration,
+            } if pid == bridge.pid => {
+                let mut state = bridge.state.lock().unwrap();
+                state.position_us = cur_pos * 1000;
+                state.length_us = duration * 1000;
+            }
+            heos::HeosEvent::PlayerNowPlayingChanged { pid } if pid == bridge.pid => {
+                if let Ok(np) = bridge.denon.lock().unwrap().get_now_playing(pid) {
+                    bridge.state.lock().unwrap().now_playing = np;
+                }
+                notify(&player);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Emit `PropertiesChanged` for the dynamic `Player` properties. The getters
+/// read the freshly updated shared state, so clients see current values.
+fn notify(player: &InterfaceRef<Player>) {
+    let ctxt = player.signal_context().clone();
+    let iface = player.get();
+    let _ = zbus::block_on(async {
+        iface.playback_status_changed(&ctxt).await?;
+        iface.volume_changed(&ctxt).await?;
+        iface.metadata_changed(&ctxt).await
+    });
+}